@@ -24,4 +24,24 @@ pub enum FlashLoanError {
     MissingBorrowIx,
     #[msg("Overflow")]
     Overflow,
+    #[msg("Invalid instructions sysvar")]
+    InvalidInstructionsSysvar,
+    #[msg("Insufficient funds to repay principal plus fee")]
+    InsufficientRepayment,
+    #[msg("Insufficient liquidity")]
+    InsufficientLiquidity,
+    #[msg("Repayment does not match the expected amount")]
+    RepaymentMismatch,
+    #[msg("Wrong repay destination")]
+    WrongRepayDestination,
+    #[msg("Borrower cannot be the protocol")]
+    BorrowerIsProtocol,
+    #[msg("Mint mismatch")]
+    MintMismatch,
+    #[msg("Repayment failed")]
+    RepaymentFailed,
+    #[msg("Invalid ATA owner")]
+    InvalidAtaOwner,
+    #[msg("Unexpected outflow from the pool")]
+    UnexpectedPoolOutflow,
 }
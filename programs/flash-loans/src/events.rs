@@ -0,0 +1,11 @@
+use anchor_lang::prelude::*;
+
+/// Emitted when `repay` catches a broken accounting invariant, just before it reverts.
+/// The transaction fails, but its logs keep the event for post-mortems.
+#[event]
+pub struct InvariantViolation {
+    /// Code of the `FlashLoanError` the instruction fails with.
+    pub code: u32,
+    /// Expected vs. observed values behind the violation.
+    pub details: String,
+}
@@ -0,0 +1,70 @@
+use anchor_lang::prelude::*;
+
+use crate::error::FlashLoanError;
+
+/// Flash loan fee in basis points, charged on the principal at repay.
+#[constant]
+pub const FEE_BPS: u64 = 500;
+
+/// Fee owed on a loan of `amount`, rounded down.
+pub fn flash_fee(amount: u64) -> Result<u64> {
+    let fee = (amount as u128)
+        .checked_mul(FEE_BPS as u128)
+        .and_then(|fee| fee.checked_div(10_000))
+        .ok_or(FlashLoanError::Overflow)?;
+    Ok(fee as u64)
+}
+
+/// Principal plus fee that `repay` pulls from the borrower.
+///
+/// A zero amount is rejected, since the zero transfer would quietly succeed and hide whatever
+/// produced it.
+pub fn required_repayment(amount: u64) -> Result<u64> {
+    require!(amount > 0, FlashLoanError::InvalidAmount);
+    Ok(amount
+        .checked_add(flash_fee(amount)?)
+        .ok_or(FlashLoanError::Overflow)?)
+}
+
+/// Net profit, in the borrowed token, of a loan whose strategy leaves the borrower holding
+/// `gross_return` before repaying.
+///
+/// `ata_rent` is what the borrower values the ATA rent at in the same token, zero when the
+/// borrower ATA already exists. A negative result means the loan loses money.
+pub fn net_profit(borrow_amount: u64, gross_return: u64, ata_rent: u64) -> Result<i128> {
+    let cost = required_repayment(borrow_amount)? as i128 + ata_rent as i128;
+    Ok(gross_return as i128 - cost)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profitable_loan() {
+        // 10_000 borrowed owes 10_500, so 10_600 leaves 100 after a zero rent
+        assert_eq!(net_profit(10_000, 10_600, 0).unwrap(), 100);
+        assert_eq!(net_profit(10_000, 10_600, 40).unwrap(), 60);
+    }
+
+    #[test]
+    fn unprofitable_loan() {
+        assert_eq!(net_profit(10_000, 10_500, 0).unwrap(), 0);
+        assert_eq!(net_profit(10_000, 10_400, 10).unwrap(), -110);
+    }
+
+    #[test]
+    fn zero_repayment() {
+        assert_eq!(
+            required_repayment(0).unwrap_err(),
+            FlashLoanError::InvalidAmount.into()
+        );
+        assert_eq!(required_repayment(1).unwrap(), 1);
+    }
+
+    #[test]
+    fn repayment_overflow() {
+        assert!(required_repayment(u64::MAX).is_err());
+        assert!(net_profit(u64::MAX, u64::MAX, 0).is_err());
+    }
+}
@@ -1,10 +1,7 @@
 use anchor_lang::{
     prelude::*,
-    solana_program::{
-        hash::hash,
-        sysvar::instructions::{
-            load_current_index_checked, load_instruction_at_checked, ID as INSTRUCTIONS_SYSVAR_ID,
-        },
+    solana_program::sysvar::instructions::{
+        load_current_index_checked, load_instruction_at_checked, ID as INSTRUCTIONS_SYSVAR_ID,
     },
 };
 use anchor_spl::{
@@ -12,7 +9,16 @@ use anchor_spl::{
     token::{transfer, Mint, Token, TokenAccount, Transfer},
 };
 
-use crate::error::FlashLoanError;
+use crate::{
+    error::FlashLoanError,
+    events::InvariantViolation,
+    fee::required_repayment,
+    introspection::{
+        check_borrow_ix, check_borrow_position, check_instructions_sysvar, check_no_pool_outflow,
+        check_repay_ix, find_repay_ix, load_instruction_count,
+    },
+    pda::{protocol_signer_seeds, PROTOCOL_SEED},
+};
 
 #[derive(Accounts)]
 pub struct Loan<'info> {
@@ -20,7 +26,7 @@ pub struct Loan<'info> {
     pub borrower: Signer<'info>,
 
     #[account(
-        seeds = [b"protocol".as_ref()],
+        seeds = [PROTOCOL_SEED],
         bump,
     )]
     pub protocol: SystemAccount<'info>,
@@ -55,101 +61,119 @@ impl<'info> Loan<'info> {
     pub fn borrow(&mut self, borrow_amount: u64, protocol_bump: u8) -> Result<()> {
         //verify valid amount
         require!(borrow_amount > 0, FlashLoanError::InvalidAmount);
+        self.check_ata_owners()?;
 
-        //define vars for signed transfer -> protocol pda to user pda (ata for both)
-        let token_program = self.token_program.to_account_info();
-
-        let accounts = Transfer {
-            from: self.protocol_ata.to_account_info(),
-            to: self.borrower_ata.to_account_info(),
-            authority: self.protocol.to_account_info(),
-        };
-        let seeds = &[b"protocol".as_ref(), &[protocol_bump]];
-        let signer_seeds = &[&seeds[..]];
-
-        //cpi context
-        let cpi_ctx = CpiContext::new_with_signer(token_program, accounts, signer_seeds);
+        //the protocol pda lending to itself would make the accounting meaningless
+        require_keys_neq!(
+            self.borrower.key(),
+            self.protocol.key(),
+            FlashLoanError::BorrowerIsProtocol
+        );
 
-        //transfer
-        transfer(cpi_ctx, borrow_amount)?;
+        //an empty pool can't lend anything, fail before the token program does
+        require!(
+            self.protocol_ata.amount > 0,
+            FlashLoanError::InsufficientLiquidity
+        );
 
         //instruction introspection - looking into further ix before they even run
+        //done before the transfer so a malformed transaction fails before paying for the cpi
         let ixs = self.sysvar_instructions.to_account_info();
+        check_instructions_sysvar(ixs.key)?;
 
         //repay ix checks
         let current_index = load_current_index_checked(&ixs)?;
-        require_eq!(current_index, 0, FlashLoanError::InvalidIx);
+        debug_msg!("borrow: current instruction index {}", current_index);
 
         // checking how many instruction we have in this transaction
-        let instruction_sysvar = ixs.try_borrow_data()?;
-        let len = u16::from_le_bytes(instruction_sysvar[0..2].try_into().unwrap());
+        let len = load_instruction_count(&ixs.try_borrow_data()?)?;
+        debug_msg!("borrow: {} instructions in transaction", len);
+        check_borrow_position(current_index, len)?;
+
+        // nothing else in the transaction may move funds out of the pool behind the repay's back,
+        // checked first so a trailing drain is reported as such rather than as a missing repay
+        for index in 1..len {
+            let ix = load_instruction_at_checked(index, &ixs)?;
+            check_no_pool_outflow(&ix, &self.protocol_ata.key())?;
+        }
 
-        // ensuring we have a repay ix
-        if let Ok(repay_ix) = load_instruction_at_checked(len as usize - 1, &ixs) {
-            // ix checks
-            require_keys_eq!(
+        // ensuring we have a repay ix, possibly followed by the borrower's own cleanup
+        let borrower = self.borrower.key();
+        if let Ok(repay_ix) = find_repay_ix(len, &borrower, |index| {
+            load_instruction_at_checked(index, &ixs).ok()
+        }) {
+            debug_msg!(
+                "borrow: repay candidate program {} with {} accounts",
                 repay_ix.program_id,
-                crate::ID,
-                FlashLoanError::InvalidProgram
-            );
-            // checking if this is a repay instruction by checking the discriminator
-            // For Anchor programs, the discriminator is the first 8 bytes
-            let repay_discriminator: [u8; 8] =
-                hash(b"global:repay").to_bytes()[..8].try_into().unwrap();
-            require!(
-                repay_ix.data[0..8].eq(&repay_discriminator),
-                FlashLoanError::InvalidIx
-            );
-
-            // We could check the Wallet and Mint separately but by checking the ATA we do this automatically
-            require_keys_eq!(
-                repay_ix
-                    .accounts
-                    .get(3)
-                    .ok_or(FlashLoanError::InvalidBorrowerAta)?
-                    .pubkey,
-                self.borrower_ata.key(),
-                FlashLoanError::InvalidBorrowerAta
-            );
-            require_keys_eq!(
-                repay_ix
-                    .accounts
-                    .get(4)
-                    .ok_or(FlashLoanError::InvalidProtocolAta)?
-                    .pubkey,
-                self.protocol_ata.key(),
-                FlashLoanError::InvalidProtocolAta
+                repay_ix.accounts.len()
             );
+            check_repay_ix(
+                &repay_ix,
+                &self.mint.key(),
+                &self.borrower_ata.key(),
+                &self.protocol_ata.key(),
+            )?;
         } else {
+            debug_msg!("borrow: no repay instruction found from index {}", len - 1);
             return Err(FlashLoanError::MissingRepayIx.into());
         }
 
+        //define vars for signed transfer -> protocol pda to user pda (ata for both)
+        let token_program = self.token_program.to_account_info();
+
+        let accounts = Transfer {
+            from: self.protocol_ata.to_account_info(),
+            to: self.borrower_ata.to_account_info(),
+            authority: self.protocol.to_account_info(),
+        };
+        let bump = [protocol_bump];
+        let seeds = protocol_signer_seeds(&bump);
+        let signer_seeds = &[&seeds[..]];
+
+        //cpi context
+        let cpi_ctx = CpiContext::new_with_signer(token_program, accounts, signer_seeds);
+
+        //transfer
+        transfer(cpi_ctx, borrow_amount)?;
+
         Ok(())
     }
 
-    pub fn repay(&mut self) -> Result<()> {
+    pub fn repay(&mut self, expected_repayment: u64) -> Result<()> {
         let ixs = self.sysvar_instructions.to_account_info();
-
-        let mut amount_borrowed: u64;
-
-        if let Ok(borrow_ix) = load_instruction_at_checked(0, &ixs) {
-            // checking the amount borrowed
-            let mut borrowed_data: [u8; 8] = [0u8; 8];
-            borrowed_data.copy_from_slice(&borrow_ix.data[8..16]);
-            amount_borrowed = u64::from_le_bytes(borrowed_data)
+        check_instructions_sysvar(ixs.key)?;
+        self.check_ata_owners()?;
+
+        let amount_borrowed = if let Ok(borrow_ix) = load_instruction_at_checked(0, &ixs) {
+            check_borrow_ix(
+                &borrow_ix,
+                &self.borrower_ata.key(),
+                &self.protocol_ata.key(),
+            )?
         } else {
+            debug_msg!("repay: no borrow instruction at index 0");
             return Err(FlashLoanError::MissingBorrowIx.into());
-        }
+        };
+        debug_msg!("repay: borrow instruction amount {}", amount_borrowed);
 
         // adding the fee to the amount borrowed (In our case we hardcoded it to 500 basis point)
-        let fee = (amount_borrowed as u128)
-            .checked_mul(500)
-            .unwrap()
-            .checked_div(10_000)
-            .ok_or(FlashLoanError::Overflow)? as u64;
-        amount_borrowed = amount_borrowed
-            .checked_add(fee)
-            .ok_or(FlashLoanError::Overflow)?;
+        let amount_borrowed = required_repayment(amount_borrowed)?;
+
+        // the client pins what it expects to pay so a fee change or introspection bug can't surprise it
+        require_eq!(
+            amount_borrowed,
+            expected_repayment,
+            FlashLoanError::RepaymentMismatch
+        );
+
+        // fail with a readable error instead of the token program's insufficient funds
+        require!(
+            self.borrower_ata.amount >= amount_borrowed,
+            FlashLoanError::InsufficientRepayment
+        );
+
+        let borrower_balance = self.borrower_ata.amount;
+        let protocol_balance = self.protocol_ata.amount;
 
         // transfering the funds from the protocol to the borrower
         transfer(
@@ -163,6 +187,46 @@ impl<'info> Loan<'info> {
             ),
             amount_borrowed,
         )?;
+
+        // the cpi succeeding should mean this already holds, but check the balances actually moved
+        self.borrower_ata.reload()?;
+        self.protocol_ata.reload()?;
+        let expected_borrower = borrower_balance - amount_borrowed;
+        let expected_protocol = protocol_balance
+            .checked_add(amount_borrowed)
+            .ok_or(FlashLoanError::Overflow)?;
+        if self.borrower_ata.amount != expected_borrower
+            || self.protocol_ata.amount != expected_protocol
+        {
+            // the revert discards state but not logs, so leave a trace for whoever investigates
+            emit!(InvariantViolation {
+                code: FlashLoanError::RepaymentFailed.into(),
+                details: format!(
+                    "borrower ata expected {} got {}, protocol ata expected {} got {}",
+                    expected_borrower,
+                    self.borrower_ata.amount,
+                    expected_protocol,
+                    self.protocol_ata.amount
+                ),
+            });
+            return Err(FlashLoanError::RepaymentFailed.into());
+        }
+        Ok(())
+    }
+
+    // the associated_token constraints already guarantee this, asserting it keeps the invariant
+    // if they're ever loosened
+    fn check_ata_owners(&self) -> Result<()> {
+        require_keys_eq!(
+            self.borrower_ata.owner,
+            self.borrower.key(),
+            FlashLoanError::InvalidAtaOwner
+        );
+        require_keys_eq!(
+            self.protocol_ata.owner,
+            self.protocol.key(),
+            FlashLoanError::InvalidAtaOwner
+        );
         Ok(())
     }
 }
@@ -0,0 +1,712 @@
+use anchor_lang::{
+    prelude::*,
+    solana_program::{
+        instruction::Instruction, sysvar::instructions::ID as INSTRUCTIONS_SYSVAR_ID,
+    },
+};
+
+use anchor_spl::{
+    associated_token::get_associated_token_address,
+    token::spl_token::{self, instruction::TokenInstruction},
+};
+
+use crate::{
+    error::FlashLoanError, fee::required_repayment, instruction, pda::find_protocol_address,
+};
+
+/// Cleanup instructions allowed after the repay, so wallets can close token accounts or unwrap
+/// SOL once the loan has settled.
+pub const MAX_CLEANUP_IXS: usize = 2;
+
+/// Checks that `key` is the instructions sysvar. The handlers' address constraint already covers
+/// this, but introspection is the whole security model so both handlers re-check it.
+pub(crate) fn check_instructions_sysvar(key: &Pubkey) -> Result<()> {
+    require_keys_eq!(
+        *key,
+        INSTRUCTIONS_SYSVAR_ID,
+        FlashLoanError::InvalidInstructionsSysvar
+    );
+    Ok(())
+}
+
+/// Reads the instruction count from the instructions sysvar header.
+///
+/// The runtime exposes no checked accessor for the count, so the header is parsed here: a
+/// little-endian u16 count followed by one u16 offset per instruction. The data must be long
+/// enough to hold that offset table before the count is trusted, which catches a layout change
+/// instead of indexing garbage.
+pub(crate) fn load_instruction_count(instruction_sysvar: &[u8]) -> Result<usize> {
+    let header: [u8; 2] = instruction_sysvar
+        .get(0..2)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(FlashLoanError::InvalidIx)?;
+    let len = u16::from_le_bytes(header) as usize;
+    require!(
+        instruction_sysvar.len() >= 2 + len * 2,
+        FlashLoanError::InvalidIx
+    );
+    Ok(len)
+}
+
+/// Checks that a borrow sitting at `current_index` of a `len` instruction transaction is first
+/// and leaves room for its repay.
+pub fn check_borrow_position(current_index: u16, len: usize) -> Result<()> {
+    require_eq!(current_index, 0, FlashLoanError::InvalidIx);
+    // a loan needs at least the borrow and the repay, which also keeps len - 1 from underflowing
+    require!(len >= 2, FlashLoanError::InvalidIx);
+    Ok(())
+}
+
+/// Whether `ix` is a token-program close of an account the borrower owns.
+pub fn is_cleanup_ix(ix: &Instruction, borrower: &Pubkey) -> bool {
+    ix.program_id == spl_token::ID
+        && matches!(
+            TokenInstruction::unpack(&ix.data),
+            Ok(TokenInstruction::CloseAccount)
+        )
+        && ix
+            .accounts
+            .get(2)
+            .is_some_and(|meta| meta.pubkey == *borrower)
+}
+
+/// Finds the repay of a `len` instruction transaction, stepping back over at most
+/// `MAX_CLEANUP_IXS` trailing cleanup instructions. Whatever is left last must pass
+/// `check_repay_ix`, so any other trailing instruction is still rejected.
+pub fn find_repay_ix(
+    len: usize,
+    borrower: &Pubkey,
+    mut load: impl FnMut(usize) -> Option<Instruction>,
+) -> Result<Instruction> {
+    let mut index = len - 1;
+    loop {
+        let ix = load(index).ok_or(FlashLoanError::MissingRepayIx)?;
+        // index 0 is the borrow, so the repay can't move further back than index 1
+        if index > 1 && len - 1 - index < MAX_CLEANUP_IXS && is_cleanup_ix(&ix, borrower) {
+            index -= 1;
+            continue;
+        }
+        return Ok(ix);
+    }
+}
+
+/// Checks that `borrow_ix` is this program's borrow against the repay's ATAs, and returns the
+/// amount it borrowed.
+pub fn check_borrow_ix(
+    borrow_ix: &Instruction,
+    borrower_ata: &Pubkey,
+    protocol_ata: &Pubkey,
+) -> Result<u64> {
+    // the first ix has to be our borrow for the same borrower ATA, otherwise there's no loan to repay
+    require!(
+        borrow_ix.program_id == crate::ID
+            && borrow_ix
+                .data
+                .starts_with(instruction::Borrow::DISCRIMINATOR)
+            && borrow_ix
+                .accounts
+                .get(3)
+                .is_some_and(|meta| meta.pubkey == *borrower_ata),
+        FlashLoanError::MissingBorrowIx
+    );
+    // funds have to go back to the same protocol ATA the borrow drew from
+    require_keys_eq!(
+        borrow_ix
+            .accounts
+            .get(4)
+            .ok_or(FlashLoanError::WrongRepayDestination)?
+            .pubkey,
+        *protocol_ata,
+        FlashLoanError::WrongRepayDestination
+    );
+
+    // checking the amount borrowed
+    let borrowed_data: [u8; 8] = borrow_ix
+        .data
+        .get(8..16)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(FlashLoanError::MissingBorrowIx)?;
+    Ok(u64::from_le_bytes(borrowed_data))
+}
+
+/// Rejects a token-program instruction that debits, delegates or hands over `protocol_ata`.
+///
+/// The protocol PDA is the pool ATA's only authority, so the token program should refuse these
+/// anyway. Rejecting them up front fails the loan with a clear error and keeps the repay from
+/// being the pool's only safeguard. The borrow's own transfer is a CPI and never shows up here.
+pub fn check_no_pool_outflow(ix: &Instruction, protocol_ata: &Pubkey) -> Result<()> {
+    if ix.program_id != spl_token::ID {
+        return Ok(());
+    }
+    // every one of these takes the affected account first
+    let debits_account = matches!(
+        TokenInstruction::unpack(&ix.data),
+        Ok(TokenInstruction::Transfer { .. }
+            | TokenInstruction::TransferChecked { .. }
+            | TokenInstruction::Approve { .. }
+            | TokenInstruction::ApproveChecked { .. }
+            | TokenInstruction::SetAuthority { .. }
+            | TokenInstruction::Burn { .. }
+            | TokenInstruction::BurnChecked { .. }
+            | TokenInstruction::CloseAccount)
+    );
+    require!(
+        !(debits_account
+            && ix
+                .accounts
+                .first()
+                .is_some_and(|meta| meta.pubkey == *protocol_ata)),
+        FlashLoanError::UnexpectedPoolOutflow
+    );
+    Ok(())
+}
+
+/// Checks that `repay_ix` is this program's repay for the borrow's mint and ATAs.
+pub fn check_repay_ix(
+    repay_ix: &Instruction,
+    mint: &Pubkey,
+    borrower_ata: &Pubkey,
+    protocol_ata: &Pubkey,
+) -> Result<()> {
+    // ix checks
+    require_keys_eq!(
+        repay_ix.program_id,
+        crate::ID,
+        FlashLoanError::InvalidProgram
+    );
+    // checking if this is a repay instruction by checking the discriminator
+    // For Anchor programs, the discriminator is the first 8 bytes and is known at compile time
+    require!(
+        repay_ix.data.starts_with(instruction::Repay::DISCRIMINATOR),
+        FlashLoanError::InvalidIx
+    );
+
+    // the ATAs already imply the mint, but binding it explicitly survives account-ordering changes
+    require_keys_eq!(
+        repay_ix
+            .accounts
+            .get(2)
+            .ok_or(FlashLoanError::MintMismatch)?
+            .pubkey,
+        *mint,
+        FlashLoanError::MintMismatch
+    );
+
+    // We could check the Wallet and Mint separately but by checking the ATA we do this automatically
+    require_keys_eq!(
+        repay_ix
+            .accounts
+            .get(3)
+            .ok_or(FlashLoanError::InvalidBorrowerAta)?
+            .pubkey,
+        *borrower_ata,
+        FlashLoanError::InvalidBorrowerAta
+    );
+    require_keys_eq!(
+        repay_ix
+            .accounts
+            .get(4)
+            .ok_or(FlashLoanError::InvalidProtocolAta)?
+            .pubkey,
+        *protocol_ata,
+        FlashLoanError::InvalidProtocolAta
+    );
+    Ok(())
+}
+
+/// Runs the instruction-layout checks of `borrow` and `repay` against a planned transaction, so
+/// clients can see which one would fail before submitting.
+///
+/// Every borrow and every repay in the list is checked, in the order they would run on chain:
+/// first the `Loan` account derivations Anchor enforces, then the handler's own checks. This
+/// shares `check_borrow_position`, `check_no_pool_outflow`, `check_repay_ix` and
+/// `check_borrow_ix` with the on-chain handlers, and checks each repay's `expected_repayment`
+/// against the fee. Checks that depend on account state, such as pool liquidity or token account
+/// owners, still only happen on chain.
+pub fn validate_loan_transaction(ixs: &[Instruction]) -> Result<()> {
+    let is_ix = |ix: &Instruction, discriminator: &[u8]| {
+        ix.program_id == crate::ID && ix.data.starts_with(discriminator)
+    };
+
+    let mut borrows = ixs
+        .iter()
+        .enumerate()
+        .filter(|(_, ix)| is_ix(ix, instruction::Borrow::DISCRIMINATOR))
+        .peekable();
+    require!(borrows.peek().is_some(), FlashLoanError::MissingBorrowIx);
+
+    for (borrow_index, borrow_ix) in borrows {
+        check_loan_accounts(borrow_ix)?;
+        validate_borrow_ix(ixs, borrow_index, borrow_ix)?;
+    }
+    for repay_ix in ixs
+        .iter()
+        .filter(|ix| is_ix(ix, instruction::Repay::DISCRIMINATOR))
+    {
+        check_loan_accounts(repay_ix)?;
+        validate_repay_ix(ixs, repay_ix)?;
+    }
+    Ok(())
+}
+
+// the address checks Anchor runs on the `Loan` accounts before either handler, with the errors it
+// raises: program ids while deserializing, then the seeds, ATA and address constraints in order
+fn check_loan_accounts(ix: &Instruction) -> Result<()> {
+    let account = |index: usize| {
+        ix.accounts
+            .get(index)
+            .map(|meta| meta.pubkey)
+            .ok_or(ErrorCode::AccountNotEnoughKeys)
+    };
+
+    require_keys_eq!(
+        account(6)?,
+        anchor_spl::token::ID,
+        ErrorCode::InvalidProgramId
+    );
+    require_keys_eq!(
+        account(7)?,
+        anchor_spl::associated_token::ID,
+        ErrorCode::InvalidProgramId
+    );
+    require_keys_eq!(
+        account(8)?,
+        anchor_lang::system_program::ID,
+        ErrorCode::InvalidProgramId
+    );
+
+    let (borrower, protocol, mint) = (account(0)?, account(1)?, account(2)?);
+    require_keys_eq!(
+        protocol,
+        find_protocol_address(&crate::ID).0,
+        ErrorCode::ConstraintSeeds
+    );
+    require_keys_eq!(
+        account(3)?,
+        get_associated_token_address(&borrower, &mint),
+        ErrorCode::AccountNotAssociatedTokenAccount
+    );
+    require_keys_eq!(
+        account(4)?,
+        get_associated_token_address(&protocol, &mint),
+        ErrorCode::ConstraintAssociated
+    );
+    require_keys_eq!(
+        account(5)?,
+        INSTRUCTIONS_SYSVAR_ID,
+        ErrorCode::ConstraintAddress
+    );
+    Ok(())
+}
+
+fn validate_borrow_ix(
+    ixs: &[Instruction],
+    borrow_index: usize,
+    borrow_ix: &Instruction,
+) -> Result<()> {
+    let account = |index: usize| {
+        borrow_ix
+            .accounts
+            .get(index)
+            .map(|meta| meta.pubkey)
+            .ok_or(FlashLoanError::InvalidIx)
+    };
+
+    let borrow_amount = borrow_ix
+        .data
+        .get(8..16)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or(FlashLoanError::InvalidIx)?;
+    require!(borrow_amount > 0, FlashLoanError::InvalidAmount);
+    require_keys_neq!(account(0)?, account(1)?, FlashLoanError::BorrowerIsProtocol);
+
+    let current_index = u16::try_from(borrow_index).map_err(|_| FlashLoanError::InvalidIx)?;
+    check_borrow_position(current_index, ixs.len())?;
+
+    for ix in &ixs[1..] {
+        check_no_pool_outflow(ix, &account(4)?)?;
+    }
+
+    let repay_ix = find_repay_ix(ixs.len(), &account(0)?, |index| ixs.get(index).cloned())?;
+    check_repay_ix(&repay_ix, &account(2)?, &account(3)?, &account(4)?)
+}
+
+// the checks `repay` runs against instruction 0 once the borrow has gone through
+fn validate_repay_ix(ixs: &[Instruction], repay_ix: &Instruction) -> Result<()> {
+    let account = |index: usize| {
+        repay_ix
+            .accounts
+            .get(index)
+            .map(|meta| meta.pubkey)
+            .ok_or(FlashLoanError::InvalidIx)
+    };
+    let borrow_ix = ixs.first().ok_or(FlashLoanError::MissingBorrowIx)?;
+    let borrow_amount = check_borrow_ix(borrow_ix, &account(3)?, &account(4)?)?;
+
+    let expected_repayment = repay_ix
+        .data
+        .get(8..16)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or(FlashLoanError::InvalidIx)?;
+    require_eq!(
+        required_repayment(borrow_amount)?,
+        expected_repayment,
+        FlashLoanError::RepaymentMismatch
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anchor_lang::{solana_program::system_instruction, InstructionData};
+
+    use super::*;
+    use crate::accounts;
+
+    struct Keys {
+        borrower: Pubkey,
+        mint: Pubkey,
+        borrower_ata: Pubkey,
+        protocol_ata: Pubkey,
+    }
+
+    impl Keys {
+        fn new() -> Self {
+            Self::for_borrower(Pubkey::new_unique())
+        }
+
+        // the addresses the program derives, so only the account a test changes is wrong
+        fn for_borrower(borrower: Pubkey) -> Self {
+            let mint = Pubkey::new_unique();
+            Self {
+                borrower,
+                mint,
+                borrower_ata: get_associated_token_address(&borrower, &mint),
+                protocol_ata: crate::pda::find_protocol_ata(&crate::ID, &mint),
+            }
+        }
+
+        fn metas(&self) -> Vec<AccountMeta> {
+            accounts::Loan {
+                borrower: self.borrower,
+                protocol: find_protocol_address(&crate::ID).0,
+                mint: self.mint,
+                borrower_ata: self.borrower_ata,
+                protocol_ata: self.protocol_ata,
+                sysvar_instructions: INSTRUCTIONS_SYSVAR_ID,
+                token_program: anchor_spl::token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+            }
+            .to_account_metas(None)
+        }
+
+        fn borrow(&self, borrow_amount: u64) -> Instruction {
+            Instruction {
+                program_id: crate::ID,
+                accounts: self.metas(),
+                data: instruction::Borrow { borrow_amount }.data(),
+            }
+        }
+
+        fn repay(&self, borrow_amount: u64) -> Instruction {
+            self.repay_exact(required_repayment(borrow_amount).unwrap())
+        }
+
+        fn repay_exact(&self, expected_repayment: u64) -> Instruction {
+            Instruction {
+                program_id: crate::ID,
+                accounts: self.metas(),
+                data: instruction::Repay { expected_repayment }.data(),
+            }
+        }
+    }
+
+    fn noop() -> Instruction {
+        let key = Pubkey::new_unique();
+        system_instruction::transfer(&key, &key, 0)
+    }
+
+    fn close(owner: &Pubkey) -> Instruction {
+        spl_token::instruction::close_account(
+            &spl_token::ID,
+            &Pubkey::new_unique(),
+            owner,
+            owner,
+            &[],
+        )
+        .unwrap()
+    }
+
+    fn assert_err(result: Result<()>, expected: FlashLoanError) {
+        assert_eq!(result.unwrap_err(), expected.into());
+    }
+
+    #[test]
+    fn rejects_malformed_instruction_count() {
+        let count = |data: &[u8]| load_instruction_count(data).unwrap_err();
+        assert_eq!(count(&[]), FlashLoanError::InvalidIx.into());
+        assert_eq!(count(&[2]), FlashLoanError::InvalidIx.into());
+        // a count of 3 needs a 6 byte offset table after the header, only 4 bytes follow
+        assert_eq!(
+            count(&[3, 0, 8, 0, 16, 0]),
+            FlashLoanError::InvalidIx.into()
+        );
+        assert_eq!(load_instruction_count(&[2, 0, 6, 0, 12, 0]).unwrap(), 2);
+    }
+
+    #[test]
+    fn accepts_borrow_then_repay() {
+        let keys = Keys::new();
+        validate_loan_transaction(&[keys.borrow(1_000), keys.repay(1_000)]).unwrap();
+        validate_loan_transaction(&[keys.borrow(1_000), noop(), keys.repay(1_000)]).unwrap();
+    }
+
+    #[test]
+    fn rejects_borrow_layouts() {
+        let keys = Keys::new();
+        assert_err(
+            validate_loan_transaction(&[keys.repay(1_000)]),
+            FlashLoanError::MissingBorrowIx,
+        );
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000)]),
+            FlashLoanError::InvalidIx,
+        );
+        assert_err(
+            validate_loan_transaction(&[noop(), keys.borrow(1_000), keys.repay(1_000)]),
+            FlashLoanError::InvalidIx,
+        );
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(0), keys.repay(1_000)]),
+            FlashLoanError::InvalidAmount,
+        );
+    }
+
+    #[test]
+    fn rejects_protocol_as_borrower() {
+        let keys = Keys::for_borrower(find_protocol_address(&crate::ID).0);
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000), keys.repay(1_000)]),
+            FlashLoanError::BorrowerIsProtocol,
+        );
+    }
+
+    #[test]
+    fn allows_trailing_cleanup() {
+        let keys = Keys::new();
+        validate_loan_transaction(&[keys.borrow(1_000), keys.repay(1_000), close(&keys.borrower)])
+            .unwrap();
+        validate_loan_transaction(&[
+            keys.borrow(1_000),
+            keys.repay(1_000),
+            close(&keys.borrower),
+            close(&keys.borrower),
+        ])
+        .unwrap();
+    }
+
+    #[test]
+    fn rejects_unexpected_trailing_instructions() {
+        let keys = Keys::new();
+        assert_err(
+            validate_loan_transaction(&[
+                keys.borrow(1_000),
+                keys.repay(1_000),
+                close(&Pubkey::new_unique()),
+            ]),
+            FlashLoanError::InvalidProgram,
+        );
+        assert_err(
+            validate_loan_transaction(&[
+                keys.borrow(1_000),
+                keys.repay(1_000),
+                close(&keys.borrower),
+                close(&keys.borrower),
+                close(&keys.borrower),
+            ]),
+            FlashLoanError::InvalidProgram,
+        );
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000), close(&keys.borrower)]),
+            FlashLoanError::InvalidProgram,
+        );
+    }
+
+    #[test]
+    fn rejects_pool_outflow() {
+        let keys = Keys::new();
+        let drain = spl_token::instruction::transfer(
+            &spl_token::ID,
+            &keys.protocol_ata,
+            &Pubkey::new_unique(),
+            &keys.borrower,
+            &[],
+            1,
+        )
+        .unwrap();
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000), drain.clone(), keys.repay(1_000)]),
+            FlashLoanError::UnexpectedPoolOutflow,
+        );
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000), keys.repay(1_000), drain]),
+            FlashLoanError::UnexpectedPoolOutflow,
+        );
+
+        let takeover = spl_token::instruction::set_authority(
+            &spl_token::ID,
+            &keys.protocol_ata,
+            Some(&keys.borrower),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            &keys.borrower,
+            &[],
+        )
+        .unwrap();
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000), takeover, keys.repay(1_000)]),
+            FlashLoanError::UnexpectedPoolOutflow,
+        );
+
+        // a transfer out of the borrower's own account is none of the pool's business
+        let spend = spl_token::instruction::transfer(
+            &spl_token::ID,
+            &keys.borrower_ata,
+            &Pubkey::new_unique(),
+            &keys.borrower,
+            &[],
+            1,
+        )
+        .unwrap();
+        validate_loan_transaction(&[keys.borrow(1_000), spend, keys.repay(1_000)]).unwrap();
+    }
+
+    #[test]
+    fn rejects_underived_accounts() {
+        let keys = Keys::new();
+        let with_account = |index: usize, pubkey: Pubkey| {
+            let mut borrow = keys.borrow(1_000);
+            borrow.accounts[index].pubkey = pubkey;
+            borrow
+        };
+        let cases = [
+            (1, ErrorCode::ConstraintSeeds),
+            (3, ErrorCode::AccountNotAssociatedTokenAccount),
+            (4, ErrorCode::ConstraintAssociated),
+            (5, ErrorCode::ConstraintAddress),
+            (6, ErrorCode::InvalidProgramId),
+            (7, ErrorCode::InvalidProgramId),
+            (8, ErrorCode::InvalidProgramId),
+        ];
+        for (index, expected) in cases {
+            assert_eq!(
+                validate_loan_transaction(&[
+                    with_account(index, Pubkey::new_unique()),
+                    keys.repay(1_000)
+                ])
+                .unwrap_err(),
+                expected.into(),
+                "account {index}"
+            );
+        }
+
+        // the repay goes through the same account checks
+        let mut repay = keys.repay(1_000);
+        repay.accounts[5].pubkey = Pubkey::new_unique();
+        assert_eq!(
+            validate_loan_transaction(&[keys.borrow(1_000), repay]).unwrap_err(),
+            ErrorCode::ConstraintAddress.into()
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_expected_repayment() {
+        let keys = Keys::new();
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000), keys.repay_exact(1_000)]),
+            FlashLoanError::RepaymentMismatch,
+        );
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000), keys.repay(2_000)]),
+            FlashLoanError::RepaymentMismatch,
+        );
+        // every repay runs on chain, not just the one borrow finds last; the TS suite submits this
+        // same layout in "Should fail when an earlier repay pins the wrong amount"
+        assert_err(
+            validate_loan_transaction(&[
+                keys.borrow(1_000),
+                keys.repay_exact(1),
+                keys.repay(1_000),
+            ]),
+            FlashLoanError::RepaymentMismatch,
+        );
+    }
+
+    #[test]
+    fn rejects_repay_to_another_protocol_ata() {
+        let keys = Keys::new();
+        assert_eq!(
+            check_borrow_ix(
+                &keys.borrow(1_000),
+                &keys.borrower_ata,
+                &Pubkey::new_unique()
+            )
+            .unwrap_err(),
+            FlashLoanError::WrongRepayDestination.into()
+        );
+    }
+
+    #[test]
+    fn rejects_one_repay_for_two_borrows() {
+        let keys = Keys::new();
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000), keys.borrow(1_000), keys.repay(1_000)]),
+            FlashLoanError::InvalidIx,
+        );
+    }
+
+    #[test]
+    fn rejects_mismatched_repay() {
+        let keys = Keys::new();
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000), keys.repay(1_000), noop()]),
+            FlashLoanError::InvalidProgram,
+        );
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000), keys.borrow(1_000)]),
+            FlashLoanError::InvalidIx,
+        );
+
+        let wrong_mint = Keys {
+            borrower_ata: keys.borrower_ata,
+            protocol_ata: keys.protocol_ata,
+            ..Keys::new()
+        };
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000), wrong_mint.repay(1_000)]),
+            FlashLoanError::MintMismatch,
+        );
+
+        let wrong_borrower_ata = Keys {
+            mint: keys.mint,
+            protocol_ata: keys.protocol_ata,
+            ..Keys::new()
+        };
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000), wrong_borrower_ata.repay(1_000)]),
+            FlashLoanError::InvalidBorrowerAta,
+        );
+
+        let wrong_protocol_ata = Keys {
+            mint: keys.mint,
+            borrower_ata: keys.borrower_ata,
+            ..Keys::new()
+        };
+        assert_err(
+            validate_loan_transaction(&[keys.borrow(1_000), wrong_protocol_ata.repay(1_000)]),
+            FlashLoanError::InvalidProtocolAta,
+        );
+    }
+}
@@ -1,6 +1,25 @@
 #![allow(unexpected_cfgs, deprecated)]
+
+/// Logs introspection diagnostics when built with the `debug` feature.
+/// Compiled out otherwise so production builds pay no compute for it.
+#[cfg(feature = "debug")]
+macro_rules! debug_msg {
+    ($($arg:tt)*) => {
+        anchor_lang::prelude::msg!($($arg)*)
+    };
+}
+
+#[cfg(not(feature = "debug"))]
+macro_rules! debug_msg {
+    ($($arg:tt)*) => {};
+}
+
 pub mod error;
+pub mod events;
+pub mod fee;
 pub mod instructions;
+pub mod introspection;
+pub mod pda;
 pub mod state;
 
 use anchor_lang::prelude::*;
@@ -17,7 +36,7 @@ pub mod flash_loans {
         ctx.accounts.borrow(borrow_amount, ctx.bumps.protocol)
     }
 
-    pub fn repay(ctx: Context<Loan>) -> Result<()> {
-        ctx.accounts.repay()
+    pub fn repay(ctx: Context<Loan>, expected_repayment: u64) -> Result<()> {
+        ctx.accounts.repay(expected_repayment)
     }
 }
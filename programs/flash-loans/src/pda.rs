@@ -0,0 +1,44 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::get_associated_token_address;
+
+/// Seed of the protocol PDA, which owns every pool ATA and signs borrow transfers.
+#[constant]
+pub const PROTOCOL_SEED: &[u8] = b"protocol";
+
+/// Derives the protocol PDA and its bump for the given program id.
+pub fn find_protocol_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[PROTOCOL_SEED], program_id)
+}
+
+/// Seeds `borrow` signs the pool transfer with, for the protocol PDA's `bump`.
+pub fn protocol_signer_seeds(bump: &[u8; 1]) -> [&[u8]; 2] {
+    [PROTOCOL_SEED, bump]
+}
+
+/// Derives the protocol's pool ATA for `mint`.
+pub fn find_protocol_ata(program_id: &Pubkey, mint: &Pubkey) -> Pubkey {
+    let (protocol, _) = find_protocol_address(program_id);
+    get_associated_token_address(&protocol, mint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_canonical_bump_signs_for_protocol() {
+        let (protocol, bump) = find_protocol_address(&crate::ID);
+        assert_eq!(
+            Pubkey::create_program_address(&protocol_signer_seeds(&[bump]), &crate::ID),
+            Ok(protocol)
+        );
+
+        // every other bump either derives a different address or lands on the curve and errors
+        for other in (0..=u8::MAX).filter(|other| *other != bump) {
+            assert_ne!(
+                Pubkey::create_program_address(&protocol_signer_seeds(&[other]), &crate::ID),
+                Ok(protocol)
+            );
+        }
+    }
+}